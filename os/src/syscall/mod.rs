@@ -0,0 +1,62 @@
+//! Implementation of syscalls
+//!
+//! The single entry point to all system calls, [`syscall`], is called
+//! whenever userspace requests something from the kernel via `ecall`. It
+//! enforces the current task's `syscall_filter` (installed by
+//! `sys_set_seccomp`) before handing off to the per-syscall `sys_*`
+//! function, named and implemented in `process`.
+
+mod process;
+
+use crate::task::{count_current_syscall, maybe_trace_stop, SyscallGate};
+pub use process::*;
+
+const SYSCALL_EXIT: usize = 93;
+const SYSCALL_YIELD: usize = 124;
+const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SBRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_FORK: usize = 220;
+const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_CLONE: usize = 261;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_SPAWN: usize = 400;
+const SYSCALL_SET_SECCOMP: usize = 401;
+const SYSCALL_PTRACE: usize = 402;
+const SYSCALL_FUTEX: usize = 403;
+
+/// Handle a syscall exception with `syscall_id` and its (up to 4) arguments.
+/// Before dispatching, this gives a pending `sys_ptrace` stop request a
+/// chance to park the task (see `maybe_trace_stop`), then enforces the
+/// current task's `syscall_filter`.
+pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
+    maybe_trace_stop();
+    match count_current_syscall(syscall_id) {
+        SyscallGate::Proceed => {}
+        SyscallGate::Deny(errno) => return errno,
+    }
+    match syscall_id {
+        SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_YIELD => sys_yield(),
+        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_SBRK => sys_sbrk(args[0] as i32),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_FORK => sys_fork(),
+        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
+        SYSCALL_CLONE => sys_clone(args[0] as u32, args[1]),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_SET_SECCOMP => sys_set_seccomp(args[0], args[1] as *const usize, args[2]),
+        SYSCALL_PTRACE => sys_ptrace(args[0], args[1], args[2], args[3]),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1], args[2]),
+        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+    }
+}
@@ -1,5 +1,6 @@
 //! Process management syscalls
 //!
+use alloc::collections::BTreeSet;
 use alloc::sync::Arc;
 
 use crate::{
@@ -7,9 +8,11 @@ use crate::{
     fs::{open_file, OpenFlags},
     mm::{translated_refmut, translated_str, MapPermission, VirtAddr},
     task::{
-        add_task, current_scheduled_time, current_syscall_counter, current_task,
-        current_user_token, exit_current_and_run_next, insert_framed_area, set_current_priority,
-        suspend_current_and_run_next, unmap_framed_area, TaskStatus,
+        add_task, block_current_and_run_next, current_scheduled_time, current_syscall_counter,
+        current_task, current_user_token, exit_current_and_run_next, futex_wait, futex_wake,
+        insert_framed_area, set_current_priority, set_current_syscall_filter,
+        suspend_current_and_run_next, unmap_framed_area, CloneFlags, SeccompAction, SeccompFilter,
+        TaskStatus,
     },
     timer::{get_time_ms, get_time_us},
 };
@@ -64,6 +67,28 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
+/// Create a new task out of the caller according to `flags`. Without
+/// `CLONE_VM` this behaves exactly like `sys_fork`; with it, the new task
+/// shares the caller's address space instead of copying it, giving it a
+/// private stack carved out of that shared space (or `new_sp`, if given) and
+/// making it independently schedulable, i.e. a lightweight thread.
+pub fn sys_clone(flags: u32, new_sp: usize) -> isize {
+    trace!("kernel:pid[{}] sys_clone", current_task().unwrap().pid.0);
+    let Some(flags) = CloneFlags::from_bits(flags) else {
+        return -1;
+    };
+    let current_task = current_task().unwrap();
+    let new_task = current_task.clone_task(flags, new_sp);
+    let new_pid = new_task.getpid();
+    // modify trap context of new_task, because it returns immediately after switching
+    let trap_cx = new_task.inner_exclusive_access().get_trap_cx();
+    // for child process, clone (like fork) returns 0
+    trap_cx.x[10] = 0;
+    // add new task to scheduler
+    add_task(new_task);
+    new_pid as isize
+}
+
 pub fn sys_exec(path: *const u8) -> isize {
     trace!("kernel:pid[{}] sys_exec", current_task().unwrap().pid.0);
     let token = current_user_token();
@@ -79,45 +104,61 @@ pub fn sys_exec(path: *const u8) -> isize {
 }
 
 /// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
+/// Else if there is a live matching child but none are zombies yet, block
+/// until one of them exits and wakes us back up.
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
-    trace!(
-        "kernel::pid[{}] sys_waitpid [{}]",
-        current_task().unwrap().pid.0,
-        pid
-    );
-    let task = current_task().unwrap();
-    // find a child process
+    loop {
+        trace!(
+            "kernel::pid[{}] sys_waitpid [{}]",
+            current_task().unwrap().pid.0,
+            pid
+        );
+        let task = current_task().unwrap();
+        // find a child process
 
-    // ---- access current PCB exclusively
-    let mut inner = task.inner_exclusive_access();
-    if !inner
-        .children
-        .iter()
-        .any(|p| pid == -1 || pid as usize == p.getpid())
-    {
-        return -1;
+        // ---- access current PCB exclusively
+        let mut inner = task.inner_exclusive_access();
+        if !inner
+            .children
+            .iter()
+            .any(|p| pid == -1 || pid as usize == p.getpid())
+        {
+            return -1;
+            // ---- release current PCB
+        }
+        let pair = inner.children.iter().enumerate().find(|(_, p)| {
+            // ++++ temporarily access child PCB exclusively
+            p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.getpid())
+            // ++++ release child PCB
+        });
+        if let Some((idx, _)) = pair {
+            let child = inner.children.remove(idx);
+            // confirm that child will be deallocated after being removed from children list
+            assert_eq!(Arc::strong_count(&child), 1);
+            let found_pid = child.getpid();
+            // ++++ temporarily access child PCB exclusively
+            let exit_code = child.inner_exclusive_access().exit_code;
+            // ++++ release child PCB
+            let token = inner.get_user_token();
+            *translated_refmut(token, exit_code_ptr) = exit_code;
+            return found_pid as isize;
+        }
+        // a matching child is alive but none are zombies yet: register
+        // ourselves on each of them (if not already waiting on it from a
+        // previous trip around this loop) and block until one of them wakes
+        // us
+        for child in inner.children.iter() {
+            if pid == -1 || pid as usize == child.getpid() {
+                let mut child_inner = child.inner_exclusive_access();
+                if !child_inner.waiters.iter().any(|w| Arc::ptr_eq(w, &task)) {
+                    child_inner.waiters.push(task.clone());
+                }
+            }
+        }
+        drop(inner);
         // ---- release current PCB
+        block_current_and_run_next();
     }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily access child PCB exclusively
-        p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB
-    });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after being removed from children list
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
-        // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
-    }
-    // ---- release current PCB automatically
 }
 
 /// get time with second and microsecond
@@ -216,3 +257,185 @@ pub fn sys_set_priority(prio: isize) -> isize {
         -1
     }
 }
+
+/// Install a syscall allow/deny policy on the current task, inherited by
+/// every task it later forks, spawns or clones.
+///
+/// `default_action` is `0` for Allow or `1` for Kill. With Allow, the
+/// syscall ids in the `len`-long array at `list_ptr` are denied with
+/// `-EPERM`; with Kill, only those ids are allowed and any other syscall
+/// kills the task.
+pub fn sys_set_seccomp(default_action: usize, list_ptr: *const usize, len: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_set_seccomp",
+        current_task().unwrap().pid.0
+    );
+    let default_action = match default_action {
+        0 => SeccompAction::Allow,
+        1 => SeccompAction::Kill,
+        _ => return -1,
+    };
+    let token = current_user_token();
+    let listed: BTreeSet<usize> = (0..len)
+        .map(|i| *translated_refmut(token, unsafe { list_ptr.add(i) } as *mut usize))
+        .collect();
+    set_current_syscall_filter(SeccompFilter::new(default_action, listed));
+    0
+}
+
+/// Attach to a child and stop it at its next syscall entry
+pub const PTRACE_ATTACH: usize = 1;
+/// Let a stopped child run again
+pub const PTRACE_CONT: usize = 2;
+/// Like `PTRACE_CONT`, but also release the attachment
+pub const PTRACE_DETACH: usize = 3;
+/// Read one `usize` word out of a stopped child's address space
+pub const PTRACE_PEEKDATA: usize = 4;
+/// Write one `usize` word into a stopped child's address space
+pub const PTRACE_POKEDATA: usize = 5;
+/// Copy a stopped child's general-purpose registers out to the caller
+pub const PTRACE_GETREGS: usize = 6;
+/// Copy general-purpose registers from the caller into a stopped child
+pub const PTRACE_SETREGS: usize = 7;
+
+/// A minimal in-kernel debugger interface: a parent attaches to a child,
+/// stops it at its next syscall entry (see `maybe_trace_stop`, called from
+/// the trap entry path), inspects or edits its registers and memory while
+/// it's stopped, then lets it continue.
+///
+/// `addr` and `data` are interpreted according to `request`: for
+/// `PTRACE_PEEKDATA`/`PTRACE_POKEDATA` they are the child word's address and
+/// (for POKE) the value to store there; for `PTRACE_GETREGS`/`PTRACE_SETREGS`
+/// `addr` is the address of a 32-`usize` buffer in the caller's own address
+/// space. Returns `-1` if `pid` isn't one of the caller's children; if
+/// `PTRACE_ATTACH` targets a child that isn't currently runnable (e.g.
+/// already a zombie, or blocked elsewhere) and so could never reach a fresh
+/// syscall entry; or if any other request targets a child that isn't
+/// currently stopped under `PTRACE_ATTACH`.
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_ptrace request={} pid={}",
+        current_task().unwrap().pid.0,
+        request,
+        pid
+    );
+    let me = current_task().unwrap();
+    let child = me
+        .inner_exclusive_access()
+        .children
+        .iter()
+        .find(|c| c.getpid() == pid)
+        .cloned();
+    let Some(child) = child else {
+        return -1;
+    };
+
+    match request {
+        PTRACE_ATTACH => {
+            // the child can only ever reach a fresh syscall entry (and so
+            // honor `trace_pending_stop`) while it's actually runnable;
+            // attaching to a zombie or a task already parked elsewhere
+            // (blocked, traced) would leave us waiting forever
+            let status = child.inner_exclusive_access().task_status;
+            if !matches!(status, TaskStatus::Ready | TaskStatus::Running) {
+                return -1;
+            }
+            child.inner_exclusive_access().tracer = Some(Arc::downgrade(&me));
+            child.inner_exclusive_access().trace_pending_stop = true;
+            // block until the child actually reaches its next syscall entry
+            // and parks itself as `Traced` (see `maybe_trace_stop`)
+            child.inner_exclusive_access().waiters.push(me.clone());
+            block_current_and_run_next();
+            0
+        }
+        PTRACE_CONT | PTRACE_DETACH => {
+            let mut child_inner = child.inner_exclusive_access();
+            if child_inner.task_status != TaskStatus::Traced {
+                return -1;
+            }
+            child_inner.task_status = TaskStatus::Ready;
+            if request == PTRACE_DETACH {
+                child_inner.tracer = None;
+            }
+            drop(child_inner);
+            add_task(child);
+            0
+        }
+        PTRACE_PEEKDATA => {
+            if child.inner_exclusive_access().task_status != TaskStatus::Traced {
+                return -1;
+            }
+            let token = child.get_user_token();
+            *translated_refmut(token, addr as *mut usize) as isize
+        }
+        PTRACE_POKEDATA => {
+            if child.inner_exclusive_access().task_status != TaskStatus::Traced {
+                return -1;
+            }
+            let token = child.get_user_token();
+            *translated_refmut(token, addr as *mut usize) = data;
+            0
+        }
+        PTRACE_GETREGS => {
+            let mut child_inner = child.inner_exclusive_access();
+            if child_inner.task_status != TaskStatus::Traced {
+                return -1;
+            }
+            let regs = child_inner.get_trap_cx().x;
+            drop(child_inner);
+            let my_token = current_user_token();
+            for (i, value) in regs.into_iter().enumerate() {
+                *translated_refmut(my_token, (addr as *mut usize).wrapping_add(i)) = value;
+            }
+            0
+        }
+        PTRACE_SETREGS => {
+            let mut child_inner = child.inner_exclusive_access();
+            if child_inner.task_status != TaskStatus::Traced {
+                return -1;
+            }
+            let my_token = current_user_token();
+            let mut regs = [0usize; 32];
+            for (i, slot) in regs.iter_mut().enumerate() {
+                *slot = *translated_refmut(my_token, (addr as *mut usize).wrapping_add(i));
+            }
+            child_inner.get_trap_cx().x = regs;
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Block until woken, if `*uaddr` still equals `val`
+pub const FUTEX_WAIT: usize = 0;
+/// Wake up to `val` tasks parked on `uaddr`
+pub const FUTEX_WAKE: usize = 1;
+
+/// A minimal futex: block or wake tasks on the userspace word at `uaddr`,
+/// keyed by its backing physical address so it also works across a
+/// `CLONE_VM`-shared address space.
+///
+/// `FUTEX_WAIT` only blocks if `*uaddr == val`; used to build a mutex, `val`
+/// doubles as the pid of the word's current holder, letting the kernel track
+/// a wait-for graph and return `-EDEADLK` instead of parking a wait that
+/// would close a cycle in it. `FUTEX_WAKE` wakes up to `val` waiters and
+/// returns how many were actually woken.
+pub fn sys_futex(uaddr: usize, op: usize, val: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_futex op={} uaddr={:#x}",
+        current_task().unwrap().pid.0,
+        op,
+        uaddr
+    );
+    let key = current_task().unwrap().inner_exclusive_access().futex_key(uaddr);
+    match op {
+        FUTEX_WAIT => {
+            if *translated_refmut(current_user_token(), uaddr as *mut usize) != val {
+                return -1;
+            }
+            futex_wait(key, val)
+        }
+        FUTEX_WAKE => futex_wake(key, val),
+        _ => -1,
+    }
+}
@@ -0,0 +1,93 @@
+//! A minimal futex: `sys_futex` blocks and wakes tasks on a userspace word,
+//! keyed by its backing physical address so it also works across a
+//! `CLONE_VM`-shared address space. Used to build a mutex, the waited value
+//! doubles as the pid of the word's current holder, which lets
+//! [`futex_wait`] track a global wait-for graph and refuse a wait that would
+//! close a cycle instead of deadlocking.
+
+use super::{add_task, block_current_and_run_next, current_task, TaskControlBlock, TaskStatus};
+use crate::sync::UPSafeCell;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// `EDEADLK`, returned instead of parking a `FUTEX_WAIT` that would close a
+/// wait-for cycle.
+pub const EDEADLK: isize = 35;
+
+struct FutexState {
+    /// Futex key (backing physical address) -> tasks parked in `FUTEX_WAIT`
+    /// on that word
+    waiters: BTreeMap<usize, Vec<Arc<TaskControlBlock>>>,
+    /// pid -> pid of the holder it's waiting for, one edge per currently
+    /// blocked `FUTEX_WAIT`
+    wait_for: BTreeMap<usize, usize>,
+}
+
+lazy_static! {
+    static ref FUTEX_STATE: UPSafeCell<FutexState> = unsafe {
+        UPSafeCell::new(FutexState {
+            waiters: BTreeMap::new(),
+            wait_for: BTreeMap::new(),
+        })
+    };
+}
+
+/// Whether blocking `waiter_pid` on `holder_pid` would close a cycle in the
+/// global wait-for graph, i.e. `holder_pid` is already (transitively)
+/// waiting for `waiter_pid`.
+fn creates_cycle(state: &FutexState, waiter_pid: usize, holder_pid: usize) -> bool {
+    let mut current = holder_pid;
+    let mut seen = BTreeSet::new();
+    loop {
+        if current == waiter_pid {
+            return true;
+        }
+        if !seen.insert(current) {
+            return false;
+        }
+        match state.wait_for.get(&current) {
+            Some(&next) => current = next,
+            None => return false,
+        }
+    }
+}
+
+/// Block the current task on `key` until [`futex_wake`]s it, unless doing so
+/// would close a wait-for cycle with `holder_pid` — in which case return
+/// `-EDEADLK` immediately instead of parking.
+pub fn futex_wait(key: usize, holder_pid: usize) -> isize {
+    let task = current_task().unwrap();
+    let waiter_pid = task.getpid();
+    {
+        let mut state = FUTEX_STATE.exclusive_access();
+        if creates_cycle(&state, waiter_pid, holder_pid) {
+            return -EDEADLK;
+        }
+        state.wait_for.insert(waiter_pid, holder_pid);
+        state.waiters.entry(key).or_default().push(task);
+    }
+    block_current_and_run_next();
+    FUTEX_STATE.exclusive_access().wait_for.remove(&waiter_pid);
+    0
+}
+
+/// Wake up to `count` tasks parked on `key`. Returns how many were woken.
+pub fn futex_wake(key: usize, count: usize) -> isize {
+    let mut state = FUTEX_STATE.exclusive_access();
+    let Some(queue) = state.waiters.get_mut(&key) else {
+        return 0;
+    };
+    let woken: Vec<_> = queue.drain(..count.min(queue.len())).collect();
+    if queue.is_empty() {
+        state.waiters.remove(&key);
+    }
+    drop(state);
+    let n = woken.len();
+    for waiter in woken {
+        waiter.inner_exclusive_access().task_status = TaskStatus::Ready;
+        add_task(waiter);
+    }
+    n as isize
+}
@@ -0,0 +1,72 @@
+//! Implementation of [`TaskManager`]
+//!
+//! It is only used to manage processes and schedule process based on ready queue.
+//! Other CPU process monitoring functions are in Processor.
+
+use super::TaskControlBlock;
+use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use lazy_static::*;
+
+/// A array of `TaskControlBlock` that is thread-safe
+///
+/// Tasks are kept in an unordered ready queue; `fetch` performs a stride
+/// scheduling pass over it rather than a plain FIFO pop, so a task's
+/// `priority` determines how often it is picked relative to its peers.
+pub struct TaskManager {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+/// A stride scheduler over the ready queue.
+impl TaskManager {
+    /// Create an empty `TaskManager`
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+    /// Add a task to `TaskManager`
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    /// Remove the `Ready` task with the smallest stride from `TaskManager` and
+    /// advance its stride by its `pass` before handing it back to the caller.
+    ///
+    /// Comparisons use wrapping arithmetic (`a` is "ahead" of `b` when
+    /// `a.wrapping_sub(b)`, read as an `isize`, is positive), so a stride that
+    /// wraps past `usize::MAX` still compares correctly as long as no two
+    /// live strides are half the `usize` range apart; since every live task's
+    /// priority is at least 2, no single `pass` can exceed `BIG_STRIDE / 2`.
+    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let min_idx = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let a_stride = a.inner_exclusive_access().stride;
+                let b_stride = b.inner_exclusive_access().stride;
+                (a_stride.wrapping_sub(b_stride) as isize).cmp(&0)
+            })
+            .map(|(idx, _)| idx)?;
+        let task = self.ready_queue.remove(min_idx).unwrap();
+        task.advance_stride();
+        Some(task)
+    }
+}
+
+lazy_static! {
+    /// TASK_MANAGER instance through lazy_static!
+    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
+        unsafe { UPSafeCell::new(TaskManager::new()) };
+}
+
+/// Add a task to the ready queue
+pub fn add_task(task: Arc<TaskControlBlock>) {
+    TASK_MANAGER.exclusive_access().add(task);
+}
+
+/// Take the `Ready` task with the smallest stride off the ready queue
+pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.exclusive_access().fetch()
+}
@@ -3,299 +3,131 @@
 //! Everything about task management, like starting and switching tasks is
 //! implemented here.
 //!
-//! A single global instance of [`TaskManager`] called `TASK_MANAGER` controls
-//! all the tasks in the operating system.
+//! A single global instance of [`Processor`] called `PROCESSOR` monitors
+//! running tasks, while [`TaskManager`] maintains tasks that are ready to
+//! run. A `Blocked` task (e.g. one in `sys_waitpid`) is removed from both
+//! until whatever it waits on (for `sys_waitpid`, an exiting child) wakes it
+//! back up.
 //!
 //! Be careful when you see `__switch` ASM function in `switch.S`. Control flow around this function
 //! might not be what you expect.
 
 mod context;
+mod futex;
+mod manager;
+mod pid;
+mod processor;
+mod seccomp;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
-use crate::config::MAX_SYSCALL_NUM;
-use crate::loader::{get_app_data, get_num_app};
-use crate::mm::{MapPermission, VirtAddr, VirtPageNum};
-use crate::sync::UPSafeCell;
-use crate::timer::get_time_ms;
-use crate::trap::TrapContext;
-use alloc::vec::Vec;
+use crate::fs::{open_file, OpenFlags};
+use alloc::sync::Arc;
 use lazy_static::*;
+pub use manager::add_task;
 use switch::__switch;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{CloneFlags, TaskControlBlock, TaskStatus};
 
 pub use context::TaskContext;
-
-/// The task manager, where all the tasks are managed.
-///
-/// Functions implemented on `TaskManager` deals with all task state transitions
-/// and task context switching. For convenience, you can find wrappers around it
-/// in the module level.
-///
-/// Most of `TaskManager` are hidden behind the field `inner`, to defer
-/// borrowing checks to runtime. You can see examples on how to use `inner` in
-/// existing functions on `TaskManager`.
-pub struct TaskManager {
-    /// total number of tasks
-    num_app: usize,
-    /// use inner value to get mutable access
-    inner: UPSafeCell<TaskManagerInner>,
-}
-
-/// The task manager inner in 'UPSafeCell'
-struct TaskManagerInner {
-    /// task list
-    tasks: Vec<TaskControlBlock>,
-    /// id of current `Running` task
-    current_task: usize,
-}
-
-lazy_static! {
-    /// a `TaskManager` global instance through lazy_static!
-    pub static ref TASK_MANAGER: TaskManager = {
-        println!("init TASK_MANAGER");
-        let num_app = get_num_app();
-        println!("num_app = {}", num_app);
-        let mut tasks: Vec<TaskControlBlock> = Vec::new();
-        for i in 0..num_app {
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
-        }
-        TaskManager {
-            num_app,
-            inner: unsafe {
-                UPSafeCell::new(TaskManagerInner {
-                    tasks,
-                    current_task: 0,
-                })
-            },
-        }
-    };
-}
-
-impl TaskManager {
-    /// Run the first task in task list.
-    ///
-    /// Generally, the first task in task list is an idle task (we call it zero process later).
-    /// But in ch4, we load apps statically, so the first task is a real app.
-    fn run_first_task(&self) -> ! {
-        let mut inner = self.inner.exclusive_access();
-        let next_task = &mut inner.tasks[0];
-        next_task.task_status = TaskStatus::Running;
-        let next_task_cx_ptr = &next_task.task_cx as *const TaskContext;
-        drop(inner);
-        let mut _unused = TaskContext::zero_init();
-        // before this, we should drop local variables that must be dropped manually
-        unsafe {
-            __switch(&mut _unused as *mut _, next_task_cx_ptr);
-        }
-        panic!("unreachable in run_first_task!");
-    }
-
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].task_status = TaskStatus::Ready;
-    }
-
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].task_status = TaskStatus::Exited;
-    }
-
-    /// Find next task to run and return task id.
-    ///
-    /// In this case, we only return the first `Ready` task in task list.
-    fn find_next_task(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
-    }
-
-    /// Get the current 'Running' task's token.
-    fn get_current_token(&self) -> usize {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_user_token()
-    }
-
-    /// Get the current 'Running' task's trap contexts.
-    fn get_current_trap_cx(&self) -> &'static mut TrapContext {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_trap_cx()
-    }
-
-    /// Change the current 'Running' task's program break
-    pub fn change_current_program_brk(&self, size: i32) -> Option<usize> {
-        let mut inner = self.inner.exclusive_access();
-        let cur = inner.current_task;
-        inner.tasks[cur].change_program_brk(size)
-    }
-
-    /// Switch current `Running` task to the task we have found,
-    /// or there is no `Ready` task and we can exit with all applications completed
-    fn run_next_task(&self) {
-        if let Some(next) = self.find_next_task() {
-            let mut inner = self.inner.exclusive_access();
-            let current = inner.current_task;
-            let next_tcb = &mut inner.tasks[next];
-            next_tcb.task_status = TaskStatus::Running;
-            if next_tcb.scheduled_time.is_none() {
-                next_tcb.scheduled_time = Some(get_time_ms());
-            }
-            inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
-            drop(inner);
-            // before this, we should drop local variables that must be dropped manually
-            unsafe {
-                __switch(current_task_cx_ptr, next_task_cx_ptr);
-            }
-            // go back to user mode
-        } else {
-            panic!("All applications completed!");
-        }
-    }
-
-    fn count_current_syscall(&self, syscall_id: usize) {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let syscall_counter = &mut inner.tasks[current].syscall_counter;
-        if let Some(value) = syscall_counter.get_mut(&syscall_id) {
-            *value += 1;
-        } else {
-            syscall_counter.insert(syscall_id, 1);
-        }
-    }
-
-    fn get_current_syscall_counter(&self) -> [u32; MAX_SYSCALL_NUM] {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let mut result = [0; MAX_SYSCALL_NUM];
-        for (k, v) in inner.tasks[current].syscall_counter.iter() {
-            result[*k] = *v;
-        }
-        result
-    }
-
-    fn get_current_scheduled_time(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        inner.tasks[current].scheduled_time
-    }
-
-    fn insert_current_framed_area(
-        &self,
-        start_va: VirtAddr,
-        end_va: VirtAddr,
-        permission: MapPermission,
-    ) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let memory_set = &mut inner.tasks[current].memory_set;
-
-        // check whether pages would be overlapped
-        if memory_set.is_overlapped(
-            VirtPageNum::from(start_va.floor()),
-            VirtPageNum::from(end_va.ceil()),
-        ) {
-            -1
-        } else {
-            memory_set.insert_framed_area(start_va, end_va, permission);
-            0
-        }
-    }
-
-    fn unmap_current_framed_area(&self, start_va: VirtAddr, end_va: VirtAddr) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let memory_set = &mut inner.tasks[current].memory_set;
-
-        memory_set.unmap(
-            VirtPageNum::from(start_va.floor()),
-            VirtPageNum::from(end_va.ceil()),
-        )
-    }
-}
-
-/// Run the first task in task list.
-pub fn run_first_task() {
-    TASK_MANAGER.run_first_task();
-}
-
-/// Switch current `Running` task to the task we have found,
-/// or there is no `Ready` task and we can exit with all applications completed
-fn run_next_task() {
-    TASK_MANAGER.run_next_task();
-}
-
-/// Change the status of current `Running` task into `Ready`.
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
-}
-
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
-}
+pub use futex::{futex_wait, futex_wake, EDEADLK};
+pub use manager::fetch_task;
+pub use pid::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
+pub use processor::{
+    change_program_brk, count_current_syscall, current_scheduled_time, current_syscall_counter,
+    current_task, current_trap_cx, current_user_token, insert_framed_area, maybe_trace_stop,
+    run_tasks, schedule, set_current_priority, set_current_syscall_filter, take_current_task,
+    unmap_framed_area, SyscallGate,
+};
+pub use seccomp::{SeccompAction, SeccompFilter, SeccompVerdict, EPERM, SECCOMP_KILL_EXIT_CODE};
 
 /// Suspend the current 'Running' task and run the next task in task list.
 pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
-    run_next_task();
-}
+    // There must be an application running.
+    let task = take_current_task().unwrap();
 
-/// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
-    run_next_task();
-}
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    // Change status to Ready
+    task_inner.task_status = TaskStatus::Ready;
+    drop(task_inner);
+    // ---- release current PCB
 
-/// Count the syscall with id `syscall_id` called by the current 'Running' task.
-pub fn count_current_syscall(syscall_id: usize) {
-    TASK_MANAGER.count_current_syscall(syscall_id);
+    // push back to ready queue.
+    add_task(task);
+    // jump to scheduling cycle
+    schedule(task_cx_ptr);
 }
 
-/// Get the number of syscalls of the current 'Running' task.
-pub fn current_syscall_counter() -> [u32; MAX_SYSCALL_NUM] {
-    TASK_MANAGER.get_current_syscall_counter()
-}
+/// Park the current 'Running' task off the ready queue without making it
+/// `Ready` again; only an explicit wake-up (see `exit_current_and_run_next`)
+/// pushes it back onto the scheduler.
+pub fn block_current_and_run_next() {
+    let task = take_current_task().unwrap();
 
-/// Get the first scheduled time of the current 'Running' task.
-/// Return `None` if the task is not scheduled.
-pub fn current_scheduled_time() -> Option<usize> {
-    TASK_MANAGER.get_current_scheduled_time()
-}
+    // ---- access current TCB exclusively
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    task_inner.task_status = TaskStatus::Blocked;
+    drop(task_inner);
+    // ---- release current PCB
 
-/// Get the current 'Running' task's token.
-pub fn current_user_token() -> usize {
-    TASK_MANAGER.get_current_token()
+    schedule(task_cx_ptr);
 }
 
-/// Get the current 'Running' task's trap contexts.
-pub fn current_trap_cx() -> &'static mut TrapContext {
-    TASK_MANAGER.get_current_trap_cx()
-}
+/// Exit the current 'Running' task and run the next task in task list.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    // take from Processor
+    let task = take_current_task().unwrap();
+
+    // **** access current TCB exclusively
+    let mut inner = task.inner_exclusive_access();
+    // Change status to Zombie
+    inner.task_status = TaskStatus::Zombie;
+    // Record exit code
+    inner.exit_code = exit_code;
+    // wake any parent blocked in `sys_waitpid` on this task
+    inner.wake_waiters();
+    // do not move to its parent but under initproc
+
+    // ++++++ access initproc TCB exclusively
+    {
+        let mut initproc_inner = INITPROC.inner_exclusive_access();
+        for child in inner.children.iter() {
+            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+            initproc_inner.children.push(child.clone());
+        }
+    }
+    // ++++++ release parent PCB
 
-/// Change the current 'Running' task's program break
-pub fn change_program_brk(size: i32) -> Option<usize> {
-    TASK_MANAGER.change_current_program_brk(size)
+    inner.children.clear();
+    // deallocate user space, but only if no other task sharing it (see
+    // `TaskControlBlock::clone_task`), zombie or not, still holds it
+    if Arc::strong_count(&inner.memory_set) == 1 {
+        inner.memory_set.exclusive_access().recycle_data_pages();
+    }
+    drop(inner);
+    // **** release current PCB
+    // drop task manually to maintain rc correctly
+    drop(task);
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
 }
 
-/// Insert pages in range [start_va, end_va) into the current 'Running' task
-pub fn insert_framed_area(
-    start_va: VirtAddr,
-    end_va: VirtAddr,
-    permission: MapPermission,
-) -> isize {
-    TASK_MANAGER.insert_current_framed_area(start_va, end_va, permission)
+lazy_static! {
+    /// Creation of initial process
+    ///
+    /// the name "initproc" may be changed to any other app name
+    pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
+        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+        let v = inode.read_all();
+        TaskControlBlock::new(v.as_slice())
+    });
 }
 
-/// Unmap pages in range [start_va, end_va) in the curring 'Running' task
-pub fn unmap_framed_area(start_va: VirtAddr, end_va: VirtAddr) -> isize {
-    TASK_MANAGER.unmap_current_framed_area(start_va, end_va)
+/// Add init process to the manager
+pub fn add_initproc() {
+    add_task(INITPROC.clone());
 }
@@ -5,7 +5,8 @@
 //! and the replacement and transfer of control flow of different applications are executed.
 
 use super::__switch;
-use super::{fetch_task, TaskStatus};
+use super::{exit_current_and_run_next, fetch_task, TaskStatus};
+use super::{SeccompVerdict, SECCOMP_KILL_EXIT_CODE, EPERM};
 use super::{TaskContext, TaskControlBlock};
 use crate::config::MAX_SYSCALL_NUM;
 use crate::mm::{MapPermission, VirtAddr, VirtPageNum};
@@ -88,8 +89,19 @@ pub fn current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSOR.exclusive_access().current()
 }
 
-/// Count the syscall with id `syscall_id` called by the current 'Running' task.
-pub fn count_current_syscall(syscall_id: usize) {
+/// What the syscall dispatcher should do after consulting
+/// [`count_current_syscall`]'s enforcement of the current task's
+/// `syscall_filter`.
+pub enum SyscallGate {
+    /// Run the syscall as usual.
+    Proceed,
+    /// Skip the syscall and return this value (an errno) to the caller instead.
+    Deny(isize),
+}
+
+/// Count the syscall with id `syscall_id` called by the current 'Running'
+/// task, and enforce its `syscall_filter` against it.
+pub fn count_current_syscall(syscall_id: usize) -> SyscallGate {
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
     let syscall_counter = &mut inner.syscall_counter;
@@ -98,6 +110,21 @@ pub fn count_current_syscall(syscall_id: usize) {
     } else {
         syscall_counter.insert(syscall_id, 1);
     }
+    match inner.syscall_filter.as_ref().map(|f| f.verdict(syscall_id)) {
+        None | Some(SeccompVerdict::Proceed) => SyscallGate::Proceed,
+        Some(SeccompVerdict::DenyWithError) => SyscallGate::Deny(-EPERM),
+        Some(SeccompVerdict::Kill) => {
+            drop(inner);
+            drop(task);
+            exit_current_and_run_next(SECCOMP_KILL_EXIT_CODE);
+            panic!("Unreachable after a task is killed by its syscall_filter!");
+        }
+    }
+}
+
+/// Install `filter` as the current 'Running' task's syscall allow/deny policy.
+pub fn set_current_syscall_filter(filter: super::SeccompFilter) {
+    current_task().unwrap().inner_exclusive_access().syscall_filter = Some(filter);
 }
 
 /// Get the number of syscalls of the current 'Running' task.
@@ -151,8 +178,8 @@ pub fn insert_framed_area(
     permission: MapPermission,
 ) -> isize {
     let task = current_task().unwrap();
-    let mut inner = task.inner_exclusive_access();
-    let memory_set = &mut inner.memory_set;
+    let inner = task.inner_exclusive_access();
+    let mut memory_set = inner.memory_set.exclusive_access();
     // check whether pages would be overlapped
     if memory_set.is_overlapped(
         VirtPageNum::from(start_va.floor()),
@@ -171,6 +198,7 @@ pub fn unmap_framed_area(start_va: VirtAddr, end_va: VirtAddr) -> isize {
         .unwrap()
         .inner_exclusive_access()
         .memory_set
+        .exclusive_access()
         .unmap(
             VirtPageNum::from(start_va.floor()),
             VirtPageNum::from(end_va.ceil()),
@@ -181,3 +209,23 @@ pub fn unmap_framed_area(start_va: VirtAddr, end_va: VirtAddr) -> isize {
 pub fn set_current_priority(new_priority: usize) {
     current_task().unwrap().set_priority(new_priority);
 }
+
+/// Called by `crate::syscall::syscall` right before a syscall is dispatched:
+/// if a tracer has requested a stop (`sys_ptrace(PTRACE_ATTACH, ...)`), park
+/// the current task as `Traced` and wake its tracer, reusing the same wake
+/// machinery `sys_waitpid` uses. The syscall itself runs once the tracer
+/// resumes the task.
+pub fn maybe_trace_stop() {
+    let task = current_task().unwrap();
+    if !task.inner_exclusive_access().trace_pending_stop {
+        return;
+    }
+    let task = take_current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    inner.trace_pending_stop = false;
+    inner.task_status = TaskStatus::Traced;
+    inner.wake_waiters();
+    let task_cx_ptr = &mut inner.task_cx as *mut TaskContext;
+    drop(inner);
+    schedule(task_cx_ptr);
+}
@@ -0,0 +1,76 @@
+//! A minimal per-task syscall allow/deny policy, modeled after the
+//! default-action-plus-exceptions shape of Linux seccomp filters.
+
+use alloc::collections::BTreeSet;
+
+/// The exit code a task is terminated with when its `syscall_filter`
+/// resolves a syscall to [`SeccompVerdict::Kill`].
+pub const SECCOMP_KILL_EXIT_CODE: i32 = -9;
+
+/// `EPERM`, returned to userspace when a syscall is denied without killing
+/// the task.
+pub const EPERM: isize = 1;
+
+/// What happens to syscalls this filter doesn't explicitly list.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SeccompAction {
+    /// Run normally.
+    Allow,
+    /// Terminate the task instead of running it.
+    Kill,
+}
+
+/// A task's syscall policy: a default action applied to every syscall id,
+/// with `listed` holding the exceptions: against an `Allow` default the
+/// listed ids are denied with `-EPERM`, against a `Kill` default they are the
+/// only ids allowed.
+#[derive(Clone)]
+pub struct SeccompFilter {
+    default_action: SeccompAction,
+    listed: BTreeSet<usize>,
+}
+
+/// What the dispatcher should do about a syscall after consulting a
+/// [`SeccompFilter`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SeccompVerdict {
+    /// Run the syscall as usual.
+    Proceed,
+    /// Deny the syscall and return `-EPERM`, leaving the task running.
+    DenyWithError,
+    /// Kill the task instead of running the syscall.
+    Kill,
+}
+
+impl SeccompFilter {
+    /// Build a filter with `default_action` for every syscall id not in
+    /// `listed`; see [`SeccompFilter::verdict`] for those that are.
+    pub fn new(default_action: SeccompAction, listed: BTreeSet<usize>) -> Self {
+        Self {
+            default_action,
+            listed,
+        }
+    }
+
+    /// Resolve the verdict for `syscall_id`. A syscall resolves to "deny"
+    /// either because it's explicitly listed against an `Allow` default, or
+    /// because it's *not* listed against a `Kill` default (an allow-list).
+    /// Whether that denial kills the task or just returns `-EPERM` is driven
+    /// by the filter's default action: a `Kill`-by-default (strict
+    /// allow-list) filter kills, an `Allow`-by-default (deny-list) filter
+    /// only errors out.
+    pub fn verdict(&self, syscall_id: usize) -> SeccompVerdict {
+        let listed = self.listed.contains(&syscall_id);
+        let denied = match self.default_action {
+            SeccompAction::Allow => listed,
+            SeccompAction::Kill => !listed,
+        };
+        if !denied {
+            SeccompVerdict::Proceed
+        } else if self.default_action == SeccompAction::Kill {
+            SeccompVerdict::Kill
+        } else {
+            SeccompVerdict::DenyWithError
+        }
+    }
+}
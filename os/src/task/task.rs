@@ -0,0 +1,479 @@
+//! Implementation of [`TaskControlBlock`]
+
+use super::TaskContext;
+use super::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
+use super::SeccompFilter;
+use crate::config::{PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use core::cell::RefMut;
+
+/// Default priority assigned to a freshly created task.
+pub const DEFAULT_PRIORITY: usize = 16;
+/// Numerator of every task's `pass = BIG_STRIDE / priority`. Strides are
+/// `usize` and wrap around at `usize::MAX`; with `priority >= 2` no single
+/// `pass` exceeds `BIG_STRIDE / 2`.
+pub const BIG_STRIDE: usize = 0xFFFF;
+
+bitflags! {
+    /// Flags accepted by `sys_clone`, mirroring the subset of Linux's
+    /// `clone(2)` flags this kernel understands.
+    pub struct CloneFlags: u32 {
+        /// Share the caller's address space (same page table) instead of
+        /// copying it, turning the new task into a lightweight thread.
+        const CLONE_VM = 0x100;
+    }
+}
+
+/// Task control block structure
+///
+/// Directly save the contents that will not change during running
+pub struct TaskControlBlock {
+    // immutable
+    /// Process identifier
+    pub pid: PidHandle,
+    /// Index of this task within the group of tasks sharing its address
+    /// space; used to place its private user stack and trap context so
+    /// `CLONE_VM` threads don't collide. The first task of a space is `0`.
+    pub tid: usize,
+    /// Kernel stack corresponding to PID
+    pub kernel_stack: KernelStack,
+    // mutable
+    inner: UPSafeCell<TaskControlBlockInner>,
+}
+
+/// Structure containing more process content
+///
+/// Store the contents that will change during operation
+/// and are wrapped here
+pub struct TaskControlBlockInner {
+    /// The physical page number of the frame where the trap context is placed
+    pub trap_cx_ppn: PhysPageNum,
+    /// Application data can only appear before user stack
+    pub base_size: usize,
+    /// Save task context
+    pub task_cx: TaskContext,
+    /// Maintain the execution status of the current process
+    pub task_status: TaskStatus,
+    /// Application address space, shared by every task cloned with
+    /// `CLONE_VM` out of the same ancestor
+    pub memory_set: Arc<UPSafeCell<MemorySet>>,
+    /// Number of tasks ever allocated into `memory_set`, shared by every
+    /// task in the group so each new thread gets its own stack/trap-context
+    /// slot below `TRAP_CONTEXT`
+    pub thread_count: Arc<UPSafeCell<usize>>,
+    /// Parent process of the current process.
+    /// Weak will not affect the reference count of the parent
+    pub parent: Option<Weak<TaskControlBlock>>,
+    /// A vector containing TCBs of all child processes of the current process
+    pub children: Vec<Arc<TaskControlBlock>>,
+    /// Tasks parked waiting on this task: either `sys_waitpid`ing for it to
+    /// become a zombie, or (if this is the tracer) for it to stop under
+    /// ptrace. Woken and pushed back onto the ready queue when it does.
+    pub waiters: Vec<Arc<TaskControlBlock>>,
+    /// The task `sys_ptrace`-attached to this one, if any
+    pub tracer: Option<Weak<TaskControlBlock>>,
+    /// Set by `sys_ptrace(PTRACE_ATTACH, ...)`; consulted at the next
+    /// syscall entry, where this task parks itself as `Traced` and wakes
+    /// its tracer before running the syscall
+    pub trace_pending_stop: bool,
+    /// It is set when active exit or execution error occurs
+    pub exit_code: i32,
+    /// Heap bottom
+    pub heap_bottom: usize,
+    /// Program break
+    pub program_brk: usize,
+    /// Number of times each syscall has been invoked by this task
+    pub syscall_counter: BTreeMap<usize, u32>,
+    /// The first time this task was ever scheduled
+    pub scheduled_time: Option<usize>,
+    /// Scheduling priority, must stay at least 2 so `pass` never exceeds
+    /// `BIG_STRIDE / 2`
+    pub priority: usize,
+    /// Accumulated stride used by the stride scheduler; advances by
+    /// `BIG_STRIDE / priority` every time this task is selected to run
+    pub stride: usize,
+    /// Syscall allow/deny policy installed by `sys_set_seccomp`, inherited
+    /// by every task forked, spawned or cloned from this one. `None` means
+    /// every syscall is allowed.
+    pub syscall_filter: Option<SeccompFilter>,
+}
+
+impl TaskControlBlockInner {
+    /// Get the trap context
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.trap_cx_ppn.get_mut()
+    }
+    /// Get the user token
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.exclusive_access().token()
+    }
+    /// Resolve `vaddr` in this task's address space into a key suitable for
+    /// `sys_futex`: its backing physical address, so two different virtual
+    /// addresses mapping to the same physical word (e.g. across a
+    /// `CLONE_VM`-shared space) resolve to the same key.
+    pub fn futex_key(&self, vaddr: usize) -> usize {
+        let va = VirtAddr::from(vaddr);
+        let ppn = self.memory_set.exclusive_access().translate(va.floor()).unwrap().ppn();
+        usize::from(ppn) * PAGE_SIZE + va.page_offset()
+    }
+    fn get_status(&self) -> TaskStatus {
+        self.task_status
+    }
+    /// Whether this task has become a zombie
+    pub fn is_zombie(&self) -> bool {
+        self.get_status() == TaskStatus::Zombie
+    }
+    /// Wake every task parked in `self.waiters`, putting each back `Ready`
+    /// and onto the scheduler. Used both when this task exits (waking a
+    /// `sys_waitpid`er) and when it parks itself under ptrace (waking its
+    /// tracer).
+    pub fn wake_waiters(&mut self) {
+        for waiter in core::mem::take(&mut self.waiters) {
+            let mut waiter_inner = waiter.inner_exclusive_access();
+            if waiter_inner.task_status == TaskStatus::Blocked {
+                waiter_inner.task_status = TaskStatus::Ready;
+                drop(waiter_inner);
+                super::add_task(waiter);
+            }
+        }
+    }
+}
+
+/// Size of the slot below `TRAP_CONTEXT` given to each `CLONE_VM` thread
+/// (`tid >= 1`): from top to bottom, its trap context page, a guard page, its
+/// user stack and a second guard page.
+const THREAD_SLOT_SIZE: usize = USER_STACK_SIZE + 3 * PAGE_SIZE;
+
+/// Placement of the `tid`-th task's private trap context below the
+/// `TRAP_CONTEXT` page reserved for the group's first task (`tid == 0`).
+fn thread_trap_cx_position(tid: usize) -> VirtAddr {
+    VirtAddr::from(TRAP_CONTEXT - tid * THREAD_SLOT_SIZE)
+}
+
+/// Placement of the `tid`-th task's private user stack, one guard page below
+/// its trap context and one guard page above the next thread's trap context.
+fn thread_user_stack_position(tid: usize) -> (VirtAddr, VirtAddr) {
+    let top = usize::from(thread_trap_cx_position(tid)) - PAGE_SIZE;
+    (VirtAddr::from(top - USER_STACK_SIZE), VirtAddr::from(top))
+}
+
+impl TaskControlBlock {
+    /// Get the mutable reference of the inner TCB
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+    /// Get the user token
+    pub fn get_user_token(&self) -> usize {
+        self.inner_exclusive_access().get_user_token()
+    }
+    /// Get the trap context
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.inner_exclusive_access().get_trap_cx()
+    }
+    /// Get pid
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+    /// Set this task's scheduling priority; the stride scheduler requires
+    /// `priority >= 2` so `pass` can never exceed `BIG_STRIDE / 2`
+    pub fn set_priority(&self, priority: usize) {
+        self.inner_exclusive_access().priority = priority.max(2);
+    }
+    /// Advance this task's stride by its `pass = BIG_STRIDE / priority`
+    pub fn advance_stride(&self) {
+        let mut inner = self.inner_exclusive_access();
+        let pass = BIG_STRIDE / inner.priority;
+        inner.stride = inner.stride.wrapping_add(pass);
+    }
+
+    /// Create a new process, the main process and thread by `elf_data`
+    pub fn new(elf_data: &[u8]) -> Self {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        // alloc a pid and a kernel stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc(pid_handle.0);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Self {
+            pid: pid_handle,
+            tid: 0,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    thread_count: Arc::new(unsafe { UPSafeCell::new(1) }),
+                    parent: None,
+                    children: Vec::new(),
+                    waiters: Vec::new(),
+                    tracer: None,
+                    trace_pending_stop: false,
+                    exit_code: 0,
+                    heap_bottom: user_sp,
+                    program_brk: user_sp,
+                    syscall_counter: BTreeMap::new(),
+                    scheduled_time: None,
+                    priority: DEFAULT_PRIORITY,
+                    stride: 0,
+                    syscall_filter: None,
+                })
+            },
+        };
+        // prepare TrapContext in user space
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+
+    /// Load a new elf to replace the original application address space and
+    /// start execution
+    pub fn exec(&self, elf_data: &[u8]) {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+
+        // **** access current TCB exclusively
+        let mut inner = self.inner_exclusive_access();
+        // substitute memory_set; `exec` always starts a fresh, unshared
+        // address space even if this task used to share one via `clone`
+        inner.memory_set = Arc::new(unsafe { UPSafeCell::new(memory_set) });
+        inner.thread_count = Arc::new(unsafe { UPSafeCell::new(1) });
+        // update trap_cx ppn
+        inner.trap_cx_ppn = trap_cx_ppn;
+        inner.base_size = user_sp;
+        inner.heap_bottom = user_sp;
+        inner.program_brk = user_sp;
+        // initialize trap_cx
+        let trap_cx = inner.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        // **** release inner automatically
+    }
+
+    /// parent process fork the child process, copying its address space
+    /// into a fresh, independent one
+    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+        // ---- access parent PCB exclusively
+        let mut parent_inner = self.inner_exclusive_access();
+        // copy user space(include trap context)
+        let memory_set = MemorySet::from_existing_user(&parent_inner.memory_set.exclusive_access());
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        // alloc a pid and a kernel stack in kernel space
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc(pid_handle.0);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            tid: 0,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    thread_count: Arc::new(unsafe { UPSafeCell::new(1) }),
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    waiters: Vec::new(),
+                    tracer: None,
+                    trace_pending_stop: false,
+                    exit_code: 0,
+                    heap_bottom: parent_inner.heap_bottom,
+                    program_brk: parent_inner.program_brk,
+                    syscall_counter: BTreeMap::new(),
+                    scheduled_time: None,
+                    priority: DEFAULT_PRIORITY,
+                    stride: 0,
+                    syscall_filter: parent_inner.syscall_filter.clone(),
+                })
+            },
+        });
+        // add child
+        parent_inner.children.push(task_control_block.clone());
+        // modify kernel_sp in trap_cx
+        // **** access child PCB exclusively
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        // return
+        task_control_block
+        // ---- release parent PCB automatically
+        // **** release child PCB automatically
+    }
+
+    /// Create a new task out of `self` according to `flags`. Without
+    /// `CLONE_VM` this is identical to [`TaskControlBlock::fork`]; with it,
+    /// the new task shares `self`'s `memory_set` (same page-table token)
+    /// instead of copying it, and gets its own private user stack and trap
+    /// context carved out of that shared space so it can be scheduled
+    /// independently. `new_sp`, if non-zero, overrides the stack pointer the
+    /// new task resumes with (mirroring `clone(2)`); otherwise it gets the
+    /// freshly allocated thread stack.
+    pub fn clone_task(self: &Arc<Self>, flags: CloneFlags, new_sp: usize) -> Arc<Self> {
+        if !flags.contains(CloneFlags::CLONE_VM) {
+            return self.fork();
+        }
+        // ---- access parent PCB exclusively
+        let mut parent_inner = self.inner_exclusive_access();
+        let memory_set = parent_inner.memory_set.clone();
+        let thread_count = parent_inner.thread_count.clone();
+        let tid = {
+            let mut count = thread_count.exclusive_access();
+            let tid = *count;
+            *count += 1;
+            tid
+        };
+        let (stack_bottom, stack_top) = thread_user_stack_position(tid);
+        let trap_cx_va = thread_trap_cx_position(tid);
+        let trap_cx_ppn = {
+            let mut ms = memory_set.exclusive_access();
+            ms.insert_framed_area(
+                stack_bottom,
+                stack_top,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            );
+            ms.insert_framed_area(
+                trap_cx_va,
+                VirtAddr::from(usize::from(trap_cx_va) + PAGE_SIZE),
+                MapPermission::R | MapPermission::W,
+            );
+            ms.translate(trap_cx_va.into()).unwrap().ppn()
+        };
+        // alloc a pid and a kernel stack in kernel space; the new thread is
+        // still a fully independent schedulable entity, it just shares
+        // address space with its parent
+        let pid_handle = pid_alloc();
+        let kernel_stack = kstack_alloc(pid_handle.0);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            tid,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: usize::from(stack_top),
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    thread_count,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    waiters: Vec::new(),
+                    tracer: None,
+                    trace_pending_stop: false,
+                    exit_code: 0,
+                    heap_bottom: parent_inner.heap_bottom,
+                    program_brk: parent_inner.program_brk,
+                    syscall_counter: BTreeMap::new(),
+                    scheduled_time: None,
+                    priority: DEFAULT_PRIORITY,
+                    stride: 0,
+                    syscall_filter: parent_inner.syscall_filter.clone(),
+                })
+            },
+        });
+        parent_inner.children.push(task_control_block.clone());
+        // the new thread resumes right where `sys_clone` was called, just
+        // like `fork`, but on its own stack and returning 0
+        let parent_trap_cx = parent_inner.get_trap_cx() as *const TrapContext;
+        let child_trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        unsafe {
+            core::ptr::write(child_trap_cx as *mut TrapContext, core::ptr::read(parent_trap_cx));
+        }
+        child_trap_cx.kernel_sp = kernel_stack_top;
+        child_trap_cx.x[10] = 0;
+        child_trap_cx.x[2] = if new_sp != 0 {
+            new_sp
+        } else {
+            usize::from(stack_top)
+        };
+        task_control_block
+        // ---- release parent PCB automatically
+    }
+
+    /// spawn a new process directly from `elf_data`, bypassing `fork`+`exec`
+    pub fn spawn(self: &Arc<Self>, elf_data: &[u8]) -> Arc<Self> {
+        let child = Arc::new(TaskControlBlock::new(elf_data));
+        let syscall_filter = self.inner_exclusive_access().syscall_filter.clone();
+        {
+            let mut child_inner = child.inner_exclusive_access();
+            child_inner.parent = Some(Arc::downgrade(self));
+            child_inner.syscall_filter = syscall_filter;
+        }
+        self.inner_exclusive_access().children.push(child.clone());
+        child
+    }
+
+    /// get the current program break
+    pub fn change_program_brk(&self, size: i32) -> Option<usize> {
+        let mut inner = self.inner_exclusive_access();
+        let old_break = inner.program_brk;
+        let new_brk = inner.program_brk as isize + size as isize;
+        if new_brk < inner.heap_bottom as isize {
+            return None;
+        }
+        let memory_set = inner.memory_set.clone();
+        let mut memory_set = memory_set.exclusive_access();
+        let result = if size < 0 {
+            memory_set.shrink_to(VirtAddr::from(inner.heap_bottom), VirtAddr::from(new_brk as usize))
+        } else {
+            memory_set.append_to(VirtAddr::from(inner.heap_bottom), VirtAddr::from(new_brk as usize))
+        };
+        if result {
+            inner.program_brk = new_brk as usize;
+            Some(old_break)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// The execution status of a task
+pub enum TaskStatus {
+    /// ready to run
+    Ready,
+    /// running
+    Running,
+    /// parked off the ready queue, e.g. by `sys_waitpid` on a live child;
+    /// woken back to `Ready` by whatever it was waiting on
+    Blocked,
+    /// stopped under `sys_ptrace`, parked at its own request after reaching a
+    /// syscall entry with `trace_pending_stop` set; resumed only by its
+    /// tracer's `PTRACE_CONT`/`PTRACE_DETACH`
+    Traced,
+    /// exited and waiting to be reaped by its parent
+    Zombie,
+}